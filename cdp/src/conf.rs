@@ -1,13 +1,612 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use envconfig::Envconfig;
-use log::Level;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::HashMap;
+use std::str::FromStr;
 
 #[derive(Envconfig)]
 pub struct Conf {
     #[envconfig(from = "LOG_LEVEL", default = "info")]
     pub log_level: Level,
+
+    #[envconfig(from = "LOG_FILTER")]
+    pub log_filter: Option<String>,
+
+    #[envconfig(from = "LOG_FORMAT", default = "text")]
+    pub log_format: LogFormat,
+}
+
+/// Output encoding for emitted log records.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogFormat {
+    /// The current human-readable `[LEVEL target] message` line.
+    Text,
+    /// Newline-delimited JSON, one object per record.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            #[cfg(feature = "json-log")]
+            "json" => Ok(LogFormat::Json),
+            #[cfg(not(feature = "json-log"))]
+            "json" => Err(anyhow!(
+                "LOG_FORMAT=json requires the 'json-log' feature, which this build was compiled without"
+            )),
+            other => Err(anyhow!(
+                "Invalid LOG_FORMAT value '{other}': expected 'text' or 'json'"
+            )),
+        }
+    }
+}
+
+/// A single `target=level` directive parsed out of a `LOG_FILTER` spec. A bare
+/// default level is not stored as a `Directive`: [`parse_filter`] returns it
+/// separately, and [`FilteredLogger::level_for`] never matches a directive
+/// without a target.
+struct Directive {
+    target: Option<String>,
+    level: LevelFilter,
+}
+
+/// Parses the env-logger-style directive grammar: a comma-separated list of
+/// `target=level` entries plus an optional bare default level, e.g.
+/// `info,cdp::pipeline=debug,hyper=warn`. Returns the parsed directives
+/// alongside the default level to fall back on when nothing matches.
+fn parse_filter(spec: &str, fallback: LevelFilter) -> (Vec<Directive>, LevelFilter) {
+    let mut directives = Vec::new();
+    let mut default = fallback;
+    for entry in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match entry.split_once('=') {
+            Some((target, level)) => {
+                if let Ok(level) = level.parse() {
+                    directives.push(Directive {
+                        target: Some(target.to_string()),
+                        level,
+                    });
+                }
+            }
+            None => {
+                if let Ok(level) = entry.parse() {
+                    default = level;
+                }
+            }
+        }
+    }
+    (directives, default)
+}
+
+/// A `log::Log` implementation that filters records against per-module
+/// directives before handing them to an underlying formatter, so one module
+/// can be silenced while another stays at debug.
+struct FilteredLogger {
+    directives: Vec<Directive>,
+    default: LevelFilter,
+    format: LogFormat,
+}
+
+impl FilteredLogger {
+    /// Finds the directive whose target is the longest prefix of `target`,
+    /// falling back to the configured default level when nothing matches.
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .filter(|d| match &d.target {
+                Some(prefix) => target.starts_with(prefix.as_str()),
+                None => false,
+            })
+            .max_by_key(|d| d.target.as_ref().map(String::len).unwrap_or(0))
+            .map(|d| d.level)
+            .unwrap_or(self.default)
+    }
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match self.format {
+            LogFormat::Text => println!(
+                "[{} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+            LogFormat::Json => println!("{}", format_json(record)),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Renders a record as a single newline-delimited JSON object with
+/// `timestamp`, `level`, `target`, `message` and any structured key/value
+/// pairs attached via the `log` crate's `kv` API, flattened into the same
+/// object. Gated behind the `json-log` feature so text-only builds don't
+/// pull in serde_json.
+#[cfg(feature = "json-log")]
+fn format_json(record: &Record) -> String {
+    use log::kv::{Error, Key, Value, VisitSource};
+    use serde_json::{Map, Value as JsonValue};
+
+    struct KvCollector(Map<String, JsonValue>);
+
+    impl<'kvs> VisitSource<'kvs> for KvCollector {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+            self.0
+                .insert(key.to_string(), JsonValue::String(value.to_string()));
+            Ok(())
+        }
+    }
+
+    let mut fields = Map::new();
+    fields.insert("timestamp".to_string(), JsonValue::String(rfc3339_now()));
+    fields.insert(
+        "level".to_string(),
+        JsonValue::String(record.level().to_string()),
+    );
+    fields.insert(
+        "target".to_string(),
+        JsonValue::String(record.target().to_string()),
+    );
+    fields.insert(
+        "message".to_string(),
+        JsonValue::String(record.args().to_string()),
+    );
+    let mut collector = KvCollector(Map::new());
+    let _ = record.key_values().visit(&mut collector);
+    fields.extend(collector.0);
+    JsonValue::Object(fields).to_string()
+}
+
+/// Stub used when the `json-log` feature is disabled: such builds reject
+/// `LOG_FORMAT=json` when parsing it, so this only renders a `LogFormat::Json`
+/// built directly, and does so the same way as `text`, since the JSON encoder
+/// isn't compiled in.
+#[cfg(not(feature = "json-log"))]
+fn format_json(record: &Record) -> String {
+    format!("[{} {}] {}", record.level(), record.target(), record.args())
+}
+
+/// Formats the current time as RFC3339 in UTC, without pulling in a
+/// dedicated datetime dependency. Only used by the `json-log` encoder.
+#[cfg(feature = "json-log")]
+fn rfc3339_now() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let millis = now.subsec_millis();
+    let days = secs / 86_400;
+    let time_of_day = secs % 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+        time_of_day / 3600,
+        (time_of_day % 3600) / 60,
+        time_of_day % 60,
+    )
+}
+
+/// Howard Hinnant's `civil_from_days` algorithm, converting a count of days
+/// since the Unix epoch into a proleptic Gregorian (year, month, day). Only
+/// used by [`rfc3339_now`].
+#[cfg(feature = "json-log")]
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = (z - era * 146_097) as u64;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let y = yoe as i64 + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+/// Installs a `FilteredLogger` built from `conf.log_filter`, falling back to
+/// the flat `conf.log_level` when no filter is configured.
+fn install_logger(conf: &Conf) -> Result<()> {
+    let fallback = conf.log_level.to_level_filter();
+    let (directives, default) = match &conf.log_filter {
+        Some(spec) => parse_filter(spec, fallback),
+        None => (Vec::new(), fallback),
+    };
+    let max_level = directives
+        .iter()
+        .map(|d| d.level)
+        .chain(std::iter::once(default))
+        .max()
+        .unwrap_or(default);
+    log::set_boxed_logger(Box::new(FilteredLogger {
+        directives,
+        default,
+        format: conf.log_format,
+    }))
+    .context("Failed to install logger")?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Picks the `.env` file to load based on the `ENV`/`APP_ENV` variable:
+/// `production` selects `.env.production`, `development` or unset selects
+/// `.env`, and any other value is rejected so typos don't silently fall
+/// through to the default file.
+fn dotenv_path() -> Result<&'static str> {
+    let env = std::env::var("ENV")
+        .or_else(|_| std::env::var("APP_ENV"))
+        .unwrap_or_else(|_| "development".to_string());
+    match env.as_str() {
+        "production" => Ok(".env.production"),
+        "development" => Ok(".env"),
+        other => Err(anyhow!(
+            "Invalid ENV/APP_ENV value '{other}': expected one of 'production', 'development'"
+        )),
+    }
+}
+
+/// Finds the 1-based line number of `offending_line` within `path` by
+/// re-reading the file and counting newlines up to its occurrence.
+/// `dotenvy` only reports the offending line's content and a character
+/// offset within it, not a file-wide line number, so this reconstructs one
+/// by searching the raw file text. Returns `None` (rather than guessing) if
+/// `offending_line` appears more than once, since then the match is
+/// genuinely ambiguous and reporting either occurrence could be wrong.
+fn locate_line(path: &str, offending_line: &str) -> Option<usize> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    let mut occurrences = contents.match_indices(offending_line);
+    let (byte_offset, _) = occurrences.next()?;
+    if occurrences.next().is_some() {
+        return None;
+    }
+    Some(contents[..byte_offset].matches('\n').count() + 1)
+}
+
+/// Loads the `.env` file selected by [`dotenv_path`] before the real
+/// environment is read. A missing file is a soft warning since operators may
+/// rely on the real environment alone; any other I/O failure (e.g.
+/// permission denied) is a hard error, since silently proceeding would hide
+/// a real misconfiguration. A parse error is also a hard error, naming the
+/// file and, best-effort, the line, since it almost always means a typo the
+/// operator should fix rather than silently ignore. `log::warn!` isn't used
+/// here because this runs before `install_logger`, so nothing would be
+/// listening yet.
+fn load_dotenv() -> Result<Option<&'static str>> {
+    let path = dotenv_path()?;
+    match dotenvy::from_filename(path) {
+        Ok(_) => Ok(Some(path)),
+        Err(err) if err.not_found() => {
+            eprintln!("No dotenv file found at '{path}', proceeding with the real environment");
+            Ok(None)
+        }
+        Err(dotenvy::Error::LineParse(content, char_index)) => {
+            let location = match locate_line(path, &content) {
+                Some(line) => format!("line {line} ('{content}')"),
+                None => format!("'{content}' (character {char_index})"),
+            };
+            Err(anyhow!("{location}"))
+                .with_context(|| format!("Failed to parse dotenv file '{path}'"))
+        }
+        Err(err) => Err(err).with_context(|| format!("Failed to load dotenv file '{path}'")),
+    }
+}
+
+/// Variable name fragments that mark a value as sensitive. Matching is
+/// case-insensitive against the env var name, not the field name.
+const REDACTED_NAME_PATTERNS: [&str; 4] = ["SECRET", "TOKEN", "PASSWORD", "KEY"];
+
+/// Replaces `value` with `***` if `var_name` looks like it holds a secret.
+fn redact(var_name: &str, value: &str) -> String {
+    let upper = var_name.to_uppercase();
+    if REDACTED_NAME_PATTERNS.iter().any(|p| upper.contains(p)) {
+        "***".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Logs the fully resolved configuration at info level, redacting values
+/// whose variable name looks secret, so operators can confirm what the
+/// process actually parsed without leaking credentials into the logs.
+fn log_resolved_config(conf: &Conf, dotenv_file: Option<&str>) {
+    log::info!(
+        "Resolved configuration: dotenv_file={}, config_file={}, LOG_LEVEL={}, LOG_FILTER={}, LOG_FORMAT={:?}",
+        dotenv_file.unwrap_or("<none>"),
+        std::env::var("CONFIG_FILE").unwrap_or_else(|_| "<none>".to_string()),
+        redact("LOG_LEVEL", &conf.log_level.to_string()),
+        redact(
+            "LOG_FILTER",
+            conf.log_filter.as_deref().unwrap_or("<none>")
+        ),
+        conf.log_format,
+    );
+}
+
+/// Maps each `Conf` field name (as it would appear in a `CONFIG_FILE`
+/// document) to the env var `Envconfig` reads it from, so file values can be
+/// used to fill gaps left by the environment.
+const FIELD_ENV_VARS: [(&str, &str); 3] = [
+    ("log_level", "LOG_LEVEL"),
+    ("log_filter", "LOG_FILTER"),
+    ("log_format", "LOG_FORMAT"),
+];
+
+/// Parses a `CONFIG_FILE` document into a flat map of field name to its
+/// scalar value, dispatching on file extension. Non-scalar values (tables,
+/// sequences) are not meaningful for `Conf`'s flat shape and are ignored.
+fn parse_config_values(path: &str, contents: &str) -> Result<HashMap<String, String>> {
+    let table: HashMap<String, toml::Value> = if path.ends_with(".toml") {
+        toml::from_str(contents).with_context(|| format!("Failed to parse config file '{path}'"))?
+    } else if path.ends_with(".yaml") || path.ends_with(".yml") {
+        let yaml: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(contents)
+            .with_context(|| format!("Failed to parse config file '{path}'"))?;
+        yaml.into_iter()
+            .filter_map(|(k, v)| match v {
+                serde_yaml::Value::String(s) => Some((k, s)),
+                serde_yaml::Value::Number(n) => Some((k, n.to_string())),
+                serde_yaml::Value::Bool(b) => Some((k, b.to_string())),
+                _ => None,
+            })
+            .map(|(k, v)| (k, toml::Value::String(v)))
+            .collect()
+    } else {
+        return Err(anyhow!(
+            "Unsupported CONFIG_FILE extension in '{path}': expected '.toml', '.yaml' or '.yml'"
+        ));
+    };
+    Ok(table
+        .into_iter()
+        .filter_map(|(k, v)| match v {
+            toml::Value::String(s) => Some((k, s)),
+            toml::Value::Integer(i) => Some((k, i.to_string())),
+            toml::Value::Float(f) => Some((k, f.to_string())),
+            toml::Value::Boolean(b) => Some((k, b.to_string())),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Loads `CONFIG_FILE` (TOML or YAML) if set, and for every `Conf` field
+/// whose env var isn't already present in the environment, sets that env var
+/// from the file's value. This makes `Conf::init_from_env` the single
+/// authoritative merge point: real env vars always win, file values fill the
+/// gaps, and fields absent from both keep their `#[envconfig(default = ...)]`
+/// value.
+fn load_config_file() -> Result<()> {
+    let path = match std::env::var("CONFIG_FILE") {
+        Ok(path) => path,
+        Err(_) => return Ok(()),
+    };
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file '{path}'"))?;
+    let values = parse_config_values(&path, &contents)?;
+    for (field, var) in FIELD_ENV_VARS {
+        if std::env::var(var).is_err() {
+            if let Some(value) = values.get(field) {
+                std::env::set_var(var, value);
+            }
+        }
+    }
+    Ok(())
 }
 
 pub fn init() -> Result<Conf> {
-    Conf::init_from_env().context("Failed to parse environment variable(s)")
+    let dotenv_file = load_dotenv()?;
+    load_config_file()?;
+    let conf = Conf::init_from_env().context("Failed to parse environment variable(s)")?;
+    install_logger(&conf)?;
+    log_resolved_config(&conf, dotenv_file);
+    Ok(conf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_filter_splits_targets_and_default() {
+        let (directives, default) =
+            parse_filter("info,cdp::pipeline=debug,hyper=warn", LevelFilter::Error);
+        assert_eq!(default, LevelFilter::Info);
+        assert_eq!(directives.len(), 2);
+        assert_eq!(directives[0].target.as_deref(), Some("cdp::pipeline"));
+        assert_eq!(directives[0].level, LevelFilter::Debug);
+        assert_eq!(directives[1].target.as_deref(), Some("hyper"));
+        assert_eq!(directives[1].level, LevelFilter::Warn);
+    }
+
+    #[test]
+    fn parse_filter_with_no_bare_default_keeps_fallback() {
+        let (directives, default) = parse_filter("cdp::pipeline=debug", LevelFilter::Info);
+        assert_eq!(default, LevelFilter::Info);
+        assert_eq!(directives.len(), 1);
+    }
+
+    #[test]
+    fn level_for_matches_longest_prefix() {
+        let logger = FilteredLogger {
+            directives: vec![
+                Directive {
+                    target: Some("cdp".to_string()),
+                    level: LevelFilter::Warn,
+                },
+                Directive {
+                    target: Some("cdp::pipeline".to_string()),
+                    level: LevelFilter::Debug,
+                },
+            ],
+            default: LevelFilter::Error,
+            format: LogFormat::Text,
+        };
+        assert_eq!(logger.level_for("cdp::pipeline::stage"), LevelFilter::Debug);
+        assert_eq!(logger.level_for("cdp::other"), LevelFilter::Warn);
+        assert_eq!(logger.level_for("hyper"), LevelFilter::Error);
+    }
+
+    #[cfg(feature = "json-log")]
+    #[test]
+    fn civil_from_days_matches_known_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_722), (2023, 12, 31));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+    }
+
+    #[cfg(feature = "json-log")]
+    #[test]
+    fn rfc3339_now_has_expected_shape() {
+        let stamp = rfc3339_now();
+        assert_eq!(stamp.len(), "2024-01-01T00:00:00.000Z".len());
+        assert!(stamp.ends_with('Z'));
+        assert_eq!(stamp.as_bytes()[4], b'-');
+        assert_eq!(stamp.as_bytes()[10], b'T');
+    }
+
+    #[test]
+    fn redact_hides_values_for_sensitive_var_names() {
+        assert_eq!(redact("API_SECRET", "hunter2"), "***");
+        assert_eq!(redact("AUTH_TOKEN", "abc"), "***");
+        assert_eq!(redact("DB_PASSWORD", "abc"), "***");
+        assert_eq!(redact("SIGNING_KEY", "abc"), "***");
+        assert_eq!(redact("api_secret", "hunter2"), "***");
+    }
+
+    #[test]
+    fn redact_passes_through_ordinary_var_names() {
+        assert_eq!(redact("LOG_LEVEL", "info"), "info");
+        assert_eq!(redact("LOG_FILTER", "cdp=debug"), "cdp=debug");
+    }
+
+    #[test]
+    fn parse_config_values_reads_toml_scalars() {
+        let values = parse_config_values(
+            "conf.toml",
+            "log_level = \"debug\"\nlog_filter = \"cdp=trace\"\n",
+        )
+        .unwrap();
+        assert_eq!(values.get("log_level"), Some(&"debug".to_string()));
+        assert_eq!(values.get("log_filter"), Some(&"cdp=trace".to_string()));
+    }
+
+    #[test]
+    fn parse_config_values_drops_non_scalar_yaml() {
+        let values = parse_config_values(
+            "conf.yaml",
+            "log_level: debug\nnested:\n  sub: true\n  other: 1\n",
+        )
+        .unwrap();
+        assert_eq!(values.get("log_level"), Some(&"debug".to_string()));
+        assert!(
+            !values.contains_key("nested"),
+            "non-scalar YAML values must be dropped, not stringified"
+        );
+    }
+
+    #[test]
+    fn parse_config_values_rejects_unknown_extension() {
+        assert!(parse_config_values("conf.ini", "log_level = debug").is_err());
+    }
+
+    /// Serializes the `load_config_file` tests below, since they mutate
+    /// process-wide env vars and would otherwise race against each other
+    /// under the default parallel test runner.
+    static ENV_MUTEX: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn with_temp_config_file(contents: &str, extension: &str, body: impl FnOnce(&str)) {
+        let path = std::env::temp_dir().join(format!(
+            "cdp-conf-test-{:?}.{extension}",
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        body(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn load_config_file_fills_gaps_left_by_the_environment() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        std::env::remove_var("LOG_LEVEL");
+        std::env::remove_var("LOG_FILTER");
+        with_temp_config_file("log_level = \"debug\"\n", "toml", |path| {
+            std::env::set_var("CONFIG_FILE", path);
+            load_config_file().unwrap();
+            assert_eq!(std::env::var("LOG_LEVEL").as_deref(), Ok("debug"));
+            std::env::remove_var("LOG_LEVEL");
+            std::env::remove_var("CONFIG_FILE");
+        });
+    }
+
+    #[test]
+    fn load_config_file_never_overrides_a_real_env_var() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        std::env::set_var("LOG_LEVEL", "error");
+        with_temp_config_file("log_level = \"debug\"\n", "toml", |path| {
+            std::env::set_var("CONFIG_FILE", path);
+            load_config_file().unwrap();
+            assert_eq!(std::env::var("LOG_LEVEL").as_deref(), Ok("error"));
+            std::env::remove_var("LOG_LEVEL");
+            std::env::remove_var("CONFIG_FILE");
+        });
+    }
+
+    #[test]
+    fn dotenv_path_prefers_env_over_app_env() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        std::env::set_var("ENV", "production");
+        std::env::set_var("APP_ENV", "development");
+        assert_eq!(dotenv_path().unwrap(), ".env.production");
+        std::env::remove_var("ENV");
+        std::env::remove_var("APP_ENV");
+    }
+
+    #[test]
+    fn dotenv_path_falls_back_to_app_env() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        std::env::remove_var("ENV");
+        std::env::set_var("APP_ENV", "production");
+        assert_eq!(dotenv_path().unwrap(), ".env.production");
+        std::env::remove_var("APP_ENV");
+    }
+
+    #[test]
+    fn dotenv_path_defaults_to_development_when_unset() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        std::env::remove_var("ENV");
+        std::env::remove_var("APP_ENV");
+        assert_eq!(dotenv_path().unwrap(), ".env");
+    }
+
+    #[test]
+    fn dotenv_path_rejects_unknown_value() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        std::env::remove_var("APP_ENV");
+        std::env::set_var("ENV", "staging");
+        assert!(dotenv_path().is_err());
+        std::env::remove_var("ENV");
+    }
+
+    #[test]
+    fn locate_line_finds_the_offending_line_in_a_multiline_file() {
+        with_temp_config_file("A=1\nB=2\nBAD LINE\nC=3\n", "env", |path| {
+            assert_eq!(locate_line(path, "BAD LINE"), Some(3));
+        });
+    }
+
+    #[test]
+    fn locate_line_gives_up_on_ambiguous_content() {
+        with_temp_config_file("DUP\nA=1\nDUP\n", "env", |path| {
+            assert_eq!(locate_line(path, "DUP"), None);
+        });
+    }
 }